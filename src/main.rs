@@ -10,12 +10,20 @@ fn main() {
         panic!("You need to provide the path of a png to read.");
     }
     match PNG::from_file_path(&args[1]) {
+        #[cfg(windows)]
         Ok(mut image) => {
             match image.show() {
                 Ok(_) => (),
                 Err(e) => panic!("{}", e)
             }
         },
+        #[cfg(not(windows))]
+        Ok(image) => {
+            match image.decode(png::PixelOrder::Rgba) {
+                Ok(decoded) => println!("Decoded a {}x{} image ({} bytes)", decoded.width, decoded.height, decoded.pixels.len()),
+                Err(e) => panic!("{}", e)
+            }
+        },
         Err(error) => panic!("{}", error)
     }
 }
@@ -1,115 +1,252 @@
+use crc32fast::Hasher;
+use miniz_oxide::inflate::stream::{inflate, InflateState};
+use miniz_oxide::{DataFormat, MZFlush, MZStatus};
+
 use crate::png::decode_error::DecodeError;
 use crate::png::decode_error::DecodeError::*;
 use crate::png::ImageMetadata;
-use crc32fast::Hasher;
 
+const PNG_SIGNATURE: [u8; 8] = [137, 80, 78, 71, 13, 10, 26, 10];
+const IHDR: [u8; 4] = *b"IHDR";
+const IDAT: [u8; 4] = *b"IDAT";
+const IEND: [u8; 4] = *b"IEND";
+
+/// One unit of progress reported by `ChunkReader::update`. Callers feed in bytes as they arrive,
+/// from a file, a socket, wherever, and get one event back per call describing whatever became
+/// available, so decoding never needs the whole file resident in memory at once.
+pub enum Decoded {
+    /// Not enough bytes have arrived yet to report anything.
+    Nothing,
+    /// The IHDR chunk finished and its CRC checked out.
+    Header(ImageMetadata),
+    /// A chunk's length and type are known; its data is about to follow.
+    #[allow(dead_code)]
+    ChunkBegin(u32, [u8; 4]),
+    /// More of an IDAT chunk's zlib stream has been inflated, as it's read, rather than only once
+    /// the whole (possibly multi-chunk) stream has arrived.
+    ImageData(Vec<u8>),
+    /// A chunk finished and its CRC checked out. IDAT chunks are included here too, still
+    /// compressed, alongside having already streamed out via `ImageData`: an IDAT's raw bytes may
+    /// double as an APNG's first frame (when the default image isn't itself part of the
+    /// animation), which needs its own independent zlib stream to decode later.
+    ChunkComplete(Chunk),
+    /// The IEND chunk was read; there is nothing left to decode.
+    ImageEnd
+}
+
+enum State {
+    Signature(usize),
+    Length,
+    Type,
+    Data,
+    Crc,
+    Done
+}
+
+/// Incrementally parses the chunk stream of a PNG, handing back a `Decoded` event as soon as one
+/// is available. `update` can be fed a buffer of any size, including one that splits a chunk's
+/// length, type, data, or CRC across calls; a partial length, type, or CRC is buffered in
+/// `pending` until it's complete, and partial chunk data accumulates in `chunk_data`. This means a truncated or still-downloading file never indexes out of bounds: a short
+/// read just leaves the reader waiting for more bytes instead of panicking.
 pub struct ChunkReader {
-    position: usize,
-    bytes: Vec<u8>
+    state: State,
+    pending: Vec<u8>,
+    length: u32,
+    chunk_type: [u8; 4],
+    data_read: usize,
+    chunk_data: Vec<u8>,
+    hasher: Hasher,
+    inflater: Box<InflateState>,
+    seen_first_chunk: bool
 }
 
 impl ChunkReader {
-    fn validate_file(bytes: &Vec<u8>) -> Result<(), DecodeError> {
-        // A valid PNG is a minimum of 57 bytes. This covers the signature, which is 8 bytes,
-        // an IHDR header chunk, which is 25 bytes (13 bytes of data), at least one IDAT chunk, and
-        // an IEND chunk. Chunks are a minimum of 12 bytes; 4 for data length, 4 for type, and
-        // 4 for a CRC. The data section can be empty)
-        if bytes.len() < 57 {
-            return Err(InvalidStructure())
-        }
-        let valid_signature: [u8; 8] = [137, 80, 78, 71, 13, 10, 26, 10];
-        if valid_signature != bytes[0..8] {
-            return Err(InvalidSignature())
-        }
-        // The first chunk of every PNG must be the header. The header's first 8 bytes must
-        // display that the data section is 13 bytes long and that the header type is b"IHDR"
-        if bytes[8..12] != [0, 0, 0, 13] || bytes[12..16] != b"IHDR".to_owned() {
-            return Err(InvalidHeader())
-        }
-        Ok(())
+    pub fn new() -> Self {
+        ChunkReader {
+            state: State::Signature(0),
+            pending: Vec::new(),
+            length: 0,
+            chunk_type: [0; 4],
+            data_read: 0,
+            chunk_data: Vec::new(),
+            hasher: Hasher::new(),
+            inflater: InflateState::new_boxed(DataFormat::Zlib),
+            seen_first_chunk: false
+        }
     }
 
-    pub fn new(bytes: Vec<u8>) -> Result<Self, DecodeError> {
-        Self::validate_file(&bytes)?;
-        // Initialize to position 33, the first byte after the signature and IHDR chunk
-        Ok(ChunkReader { position: 33, bytes })
+    /// Feeds `buf` into the decoder and returns how many of its bytes were consumed along with
+    /// the event that became available, if any. A call never blocks for more input than `buf`
+    /// provides: if the field currently being read isn't complete yet, all of `buf` is consumed
+    /// (into `pending`, or `chunk_data` for chunk data) and the next call continues it. Until the
+    /// field completes this returns `Decoded::Nothing`, except that partial IDAT data can already
+    /// yield `Decoded::ImageData`.
+    pub fn update(&mut self, buf: &[u8]) -> Result<(usize, Decoded), DecodeError> {
+        if buf.is_empty() {
+            return Err(UnexpectedEof());
+        }
+        match self.state {
+            State::Signature(matched) => self.read_signature(buf, matched),
+            State::Length => self.read_length(buf),
+            State::Type => self.read_type(buf),
+            State::Data => self.read_data(buf),
+            State::Crc => self.read_crc(buf),
+            State::Done => Ok((0, Decoded::ImageEnd))
+        }
     }
 
-    fn read_four_bytes_into_u32(&mut self) -> u32 {
-        let arr = self.read_four_bytes_into_array();
-        // PNG files are big endian (network ordering)
-        u32::from_be_bytes(arr)
+    fn read_signature(&mut self, buf: &[u8], matched: usize) -> Result<(usize, Decoded), DecodeError> {
+        let remaining = &PNG_SIGNATURE[matched..];
+        let take = remaining.len().min(buf.len());
+        if buf[..take] != remaining[..take] {
+            return Err(InvalidSignature());
+        }
+        let matched = matched + take;
+        self.state = if matched == PNG_SIGNATURE.len() { State::Length } else { State::Signature(matched) };
+        Ok((take, Decoded::Nothing))
     }
 
-    fn read_four_bytes_into_array(&mut self) -> [u8; 4] {
-        let mut buf = [0u8; 4];
-        buf.clone_from_slice(&self.bytes[self.position..self.position + 4]);
-        self.position += 4;
-        buf
+    // Buffers bytes from `buf` into `pending` until it holds `target` bytes, returning how many
+    // bytes of `buf` were consumed and whether `pending` is now complete.
+    fn fill_pending(&mut self, buf: &[u8], target: usize) -> (usize, bool) {
+        let need = target - self.pending.len();
+        let take = need.min(buf.len());
+        self.pending.extend_from_slice(&buf[..take]);
+        (take, self.pending.len() == target)
     }
 
-    fn read_chunk_data(&mut self, bytes_to_read: &u32) -> Vec<u8> {
-        let mut res: Vec<u8> = Vec::new();
-        for b in 0..*bytes_to_read as usize {
-            res.push(self.bytes[self.position + b].clone())
+    fn read_length(&mut self, buf: &[u8]) -> Result<(usize, Decoded), DecodeError> {
+        let (take, complete) = self.fill_pending(buf, 4);
+        if complete {
+            let mut arr = [0u8; 4];
+            arr.clone_from_slice(&self.pending);
+            self.length = u32::from_be_bytes(arr);
+            self.pending.clear();
+            // CRC covers this chunk's type and data, not its length, so the hasher starts fresh
+            // here rather than back at `ChunkReader::new`.
+            self.hasher = Hasher::new();
+            self.state = State::Type;
         }
-        self.position += *bytes_to_read as usize;
-        res
+        Ok((take, Decoded::Nothing))
     }
 
-    pub fn read_into_vec(&mut self, chunks: &mut Vec<Chunk>) -> Result<(), DecodeError> {
-        while self.position < self.bytes.len() {
-            let length = self.read_four_bytes_into_u32();
-            let chunk_type = self.read_four_bytes_into_array();
-            let chunk_data = self.read_chunk_data(&length);
-            let crc = self.read_four_bytes_into_u32();
-            let chunk = Chunk { length, chunk_type, chunk_data, crc };
-            // TODO: Need to support PLTE chunks
-            if chunk.chunk_type == b"PLTE".to_owned() {
-                return Err(UnsupportedFeature("PLTE chunks are not yet supported".to_owned()));
-            }
-            if !chunk.crc_is_valid() {
-                return Err(FailedChecksum());
-            }
-            if chunk.chunk_type != b"IEND".to_owned() {
-                chunks.push(chunk);
+    fn read_type(&mut self, buf: &[u8]) -> Result<(usize, Decoded), DecodeError> {
+        let (take, complete) = self.fill_pending(buf, 4);
+        if !complete {
+            return Ok((take, Decoded::Nothing));
+        }
+        self.chunk_type.clone_from_slice(&self.pending);
+        self.pending.clear();
+        self.hasher.update(&self.chunk_type);
+
+        if !self.seen_first_chunk {
+            self.seen_first_chunk = true;
+            if self.chunk_type != IHDR || self.length != 13 {
+                return Err(InvalidHeader());
             }
         }
-        Ok(())
+
+        self.data_read = 0;
+        self.chunk_data.clear();
+        self.state = if self.length == 0 { State::Crc } else { State::Data };
+        Ok((take, Decoded::ChunkBegin(self.length, self.chunk_type)))
     }
 
-    pub fn read_metadata(&self) -> Result<ImageMetadata, DecodeError> {
-        // IHDR begin at position 8 and end at 33 (non-inclusive)
-        // This means that IHDR's data begins at 16 and ends at 29 (non-inclusive)
+    fn read_data(&mut self, buf: &[u8]) -> Result<(usize, Decoded), DecodeError> {
+        let remaining = self.length as usize - self.data_read;
+        let take = remaining.min(buf.len());
+        let data = &buf[..take];
+        self.hasher.update(data);
+        self.data_read += take;
+        if self.data_read == self.length as usize {
+            self.state = State::Crc;
+        }
+        // Buffered regardless of chunk type: IDAT's raw, still-compressed bytes are also needed
+        // later if they turn out to double as an APNG's first frame.
+        self.chunk_data.extend_from_slice(data);
+
+        if self.chunk_type == IDAT {
+            let inflated = self.feed_inflate(data)?;
+            return Ok((take, if inflated.is_empty() { Decoded::Nothing } else { Decoded::ImageData(inflated) }));
+        }
+        Ok((take, Decoded::Nothing))
+    }
+
+    fn read_crc(&mut self, buf: &[u8]) -> Result<(usize, Decoded), DecodeError> {
+        let (take, complete) = self.fill_pending(buf, 4);
+        if !complete {
+            return Ok((take, Decoded::Nothing));
+        }
+        let mut arr = [0u8; 4];
+        arr.clone_from_slice(&self.pending);
+        let crc = u32::from_be_bytes(arr);
+        self.pending.clear();
+        if crc != self.hasher.clone().finalize() {
+            return Err(FailedChecksum());
+        }
+
+        if self.chunk_type == IEND {
+            self.state = State::Done;
+            return Ok((take, Decoded::ImageEnd));
+        }
+        self.state = State::Length;
+
+        if self.chunk_type == IHDR {
+            let metadata = Self::parse_ihdr(&self.chunk_data)?;
+            return Ok((take, Decoded::Header(metadata)));
+        }
+        let chunk = Chunk { length: self.length, chunk_type: self.chunk_type, chunk_data: std::mem::take(&mut self.chunk_data), crc };
+        Ok((take, Decoded::ChunkComplete(chunk)))
+    }
+
+    fn parse_ihdr(data: &[u8]) -> Result<ImageMetadata, DecodeError> {
         let mut buf = [0u8; 4];
-        buf.clone_from_slice(&self.bytes[16..20]);
+        buf.clone_from_slice(&data[0..4]);
         let width = u32::from_be_bytes(buf);
-        buf.clone_from_slice(&self.bytes[20..24]);
+        buf.clone_from_slice(&data[4..8]);
         let height = u32::from_be_bytes(buf);
-        // TODO: Support interlacing: http://www.libpng.org/pub/png/spec/1.2/PNG-DataRep.html#DR.Interlaced-data-order
-        let bit_depth = self.bytes[24].clone();
-        if bit_depth != 8 {
-            // TODO: support bit depths other than 8
-            return Err(UnsupportedFeature("A bit depth of 8 is the only supported bit depth right now".to_owned()));
-        }
-        let compression_method = self.bytes[26].clone();
-        let filter_method = self.bytes[27].clone();
-        let interlace_method = self.bytes[28].clone();
+        let bit_depth = data[8];
+        let color_type = data[9];
+        let compression_method = data[10];
+        let filter_method = data[11];
+        let interlace_method = data[12];
+        if ![1, 2, 4, 8, 16].contains(&bit_depth) {
+            return Err(UnsupportedFeature("Bit depth must be one of 1, 2, 4, 8, or 16".to_owned()));
+        }
         if filter_method != 0 || compression_method != 0 {
             return Err(UnsupportedFeature("Filter and compression methods only support 0 for each".to_owned()));
         }
-        if interlace_method != 0 {
-            return Err(UnsupportedFeature("Interlacing is not yet supported".to_owned()));
-        }
-        Ok(ImageMetadata {
-            width,
-            height,
-            bit_depth,
-            color_type: self.bytes[25].clone(),
-            compression_method,
-            filter_method,
-            interlace_method
-        })
+        // 0 = no interlacing, 1 = Adam7
+        if interlace_method != 0 && interlace_method != 1 {
+            return Err(UnsupportedFeature("Only the 'no interlace' and Adam7 interlace methods are supported".to_owned()));
+        }
+        Ok(ImageMetadata { width, height, bit_depth, color_type, compression_method, filter_method, interlace_method })
+    }
+
+    // Pushes `input` (a slice of an IDAT chunk's zlib stream) through the running inflater and
+    // returns whatever decompressed bytes came out of it. The inflater's state persists across
+    // calls, and across IDAT chunk boundaries, since an image's compressed pixel data is one
+    // continuous zlib stream that the spec allows splitting across chunks arbitrarily.
+    fn feed_inflate(&mut self, mut input: &[u8]) -> Result<Vec<u8>, DecodeError> {
+        let mut out = Vec::new();
+        let mut scratch = [0u8; 8192];
+        while !input.is_empty() {
+            let result = inflate(&mut self.inflater, input, &mut scratch, MZFlush::None);
+            out.extend_from_slice(&scratch[..result.bytes_written]);
+            input = &input[result.bytes_consumed..];
+            match result.status {
+                Ok(MZStatus::StreamEnd) => break,
+                Ok(_) => {
+                    if result.bytes_consumed == 0 && result.bytes_written == 0 {
+                        // The inflater needs more compressed bytes than this chunk has left; the
+                        // rest of the stream arrives in a later IDAT chunk.
+                        break;
+                    }
+                }
+                Err(_) => return Err(FailedDecoding())
+            }
+        }
+        Ok(out)
     }
 }
 
@@ -117,26 +254,10 @@ impl ChunkReader {
 /// of the data section, not the entire chunks length), then the chunk's type, the chunk data, and
 /// a CRC.
 pub struct Chunk {
+    #[allow(dead_code)]
     pub length: u32,
     pub chunk_type: [u8; 4],
     pub chunk_data: Vec<u8>,
+    #[allow(dead_code)]
     pub crc: u32
 }
-
-// pub struct Chunk<'a> {
-//     pub length: u32,
-//     pub chunk_type: [u8; 4],
-//     pub chunk_data:Cow<'a, [u8]>, // &'a [u8] when borrowed, Vec<u8> when owned
-//     pub crc: u32,
-// }
-
-impl Chunk {
-    fn crc_is_valid(&self) -> bool {
-        // CRC is calculated on the chunk type and chunk data but NOT the length field
-        let mut hasher = Hasher::new();
-        hasher.update(&self.chunk_type);
-        hasher.update(&self.chunk_data[..]);
-        let checksum = hasher.finalize();
-        checksum == self.crc
-    }
-}
\ No newline at end of file
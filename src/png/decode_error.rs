@@ -19,5 +19,11 @@ pub enum DecodeError {
     #[error("Failed to decode PNG data")]
     FailedDecoding(),
     #[error("The PNG header is invalid, it should be 25 bytes long and have the chunk type of 'IHDR'")]
-    InvalidHeader()
+    InvalidHeader(),
+    #[error("The scanline filter byte is not a recognized filter type")]
+    InvalidScanlineFilter(),
+    #[error("The PLTE chunk is missing or has an invalid length, it must hold 1-256 RGB triplets")]
+    BadPaletteChunk(),
+    #[error("Ran out of input before the PNG structure was complete")]
+    UnexpectedEof()
 }
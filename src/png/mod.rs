@@ -1,18 +1,29 @@
 use std::path::Path;
 use std::fs::File;
-use std::io::Read;
+use std::io::{Read, BufReader};
 use std::fmt;
 use std::fmt::Formatter;
+use std::time::Duration;
 
 use miniz_oxide::inflate::decompress_to_vec_zlib;
+
+// The GDI/winit viewer is Windows-only; everything else (parsing, filtering, the `decode` API)
+// is plain Rust and builds and runs on any platform, matching how image-rs keeps its codecs
+// separate from any particular display backend.
+#[cfg(windows)]
+use std::time::Instant;
+#[cfg(windows)]
 use winit::{
     event::{Event, WindowEvent},
     event_loop::EventLoop,
     window::WindowBuilder,
     platform::windows::WindowExtWindows
 };
+#[cfg(windows)]
 use core::mem::MaybeUninit;
+#[cfg(windows)]
 use std::mem::size_of;
+#[cfg(windows)]
 use windows_sys::Win32::{
     Graphics::Gdi::{
         BeginPaint,
@@ -20,14 +31,40 @@ use windows_sys::Win32::{
         SelectObject, GetObjectA, BITMAP, BitBlt, SRCCOPY, DeleteDC, EndPaint, DeleteObject, CreateBitmap,
     },
 };
+#[cfg(windows)]
 use windows_sys::Win32::Graphics::Gdi::HBITMAP;
 
-mod decode_error;
-use decode_error::DecodeError;
+pub mod decode_error;
+pub use decode_error::DecodeError;
 use decode_error::DecodeError::*;
 
 mod chunk;
-use chunk::{Chunk, ChunkReader};
+use chunk::{Chunk, ChunkReader, Decoded};
+
+mod animation;
+use animation::{AnimationControl, DisposeOp, BlendOp, FrameControl};
+
+#[cfg(test)]
+mod tests;
+
+/// The channel order of a `DecodedImage`'s pixel buffer.
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PixelOrder {
+    Rgba,
+    Bgra
+}
+
+/// The result of `PNG::decode`: a fully decoded, fully composited image, portable in the sense
+/// that it carries no dependency on any particular display backend.
+#[allow(dead_code)]
+#[derive(Debug)]
+pub struct DecodedImage {
+    pub width: u32,
+    pub height: u32,
+    pub order: PixelOrder,
+    pub pixels: Vec<u8>
+}
 
 #[allow(dead_code)]
 #[derive(Debug)]
@@ -41,70 +78,98 @@ pub struct ImageMetadata {
     interlace_method: u8
 }
 
-struct LastPixel {
-    a: [u8; 4],
-    b: [u8; 4],
-    c: [u8; 4],
-    d: [u8; 4]
+// Adam7 splits an image into 7 interleaved sub-images ("passes"). These tables give, per pass,
+// the starting column/row and the column/row stride within the full image.
+const ADAM7_COL_START: [usize; 7] = [0, 4, 0, 2, 0, 1, 0];
+const ADAM7_ROW_START: [usize; 7] = [0, 0, 4, 0, 2, 0, 1];
+const ADAM7_COL_STRIDE: [usize; 7] = [8, 8, 4, 4, 2, 2, 1];
+const ADAM7_ROW_STRIDE: [usize; 7] = [8, 8, 8, 4, 4, 2, 2];
+
+// Returns the (width, height) of the given Adam7 pass's sub-image; either is 0 if the pass owns
+// no pixels in an image this small.
+fn adam7_pass_dimensions(width: usize, height: usize, pass: usize) -> (usize, usize) {
+    let col_start = ADAM7_COL_START[pass];
+    let row_start = ADAM7_ROW_START[pass];
+    let pass_width = if width > col_start {
+        (width - col_start + ADAM7_COL_STRIDE[pass] - 1) / ADAM7_COL_STRIDE[pass]
+    } else {
+        0
+    };
+    let pass_height = if height > row_start {
+        (height - row_start + ADAM7_ROW_STRIDE[pass] - 1) / ADAM7_ROW_STRIDE[pass]
+    } else {
+        0
+    };
+    (pass_width, pass_height)
 }
 
-impl LastPixel {
-    fn new() -> Self {
-        Self {
-            a: [0u8; 4],
-            b: [0u8; 4],
-            c: [0u8; 4],
-            d: [0u8; 4],
-        }
+// The PNG Paeth predictor, used by filter type 4. `a` is the byte to the left, `b` the byte
+// above, `c` the byte above-and-to-the-left (all `bytes_per_pixel` bytes back from the byte being
+// reconstructed, per the PNG filtering spec).
+fn paeth_predictor(a: u8, b: u8, c: u8) -> u8 {
+    let a = a as i16;
+    let b = b as i16;
+    let c = c as i16;
+    let p = a + b - c;
+    let p_a = (p - a).abs();
+    let p_b = (p - b).abs();
+    let p_c = (p - c).abs();
+    if p_a <= p_b && p_a <= p_c {
+        a as u8
     }
-
-    fn from_decoded(decoded_bytes: &[u8], x: usize, y: usize, width: u32) -> Self {
-        fn get_pixel(decoded_bytes: &[u8], x: isize, y: isize, width: isize) -> [u8; 4] {
-            if x < 0 || y < 0 {
-                return [0u8; 4];
-            }
-            // pixel_index = pixels from current line + all pixels on each line before us
-            let pixel_index = (x + (y * width)) as usize;
-            // byte_index = pixel_index * 4, each pixel is 4 bytes (BGRA) in our decoded output to Windows
-            let byte_index = pixel_index * 4;
-            let mut buf = [0u8; 4];
-            buf.clone_from_slice(&decoded_bytes[byte_index..byte_index + 4]);
-            buf
-        }
-        let x = x as isize;
-        let y = y as isize;
-        let width = width as isize;
-        let a = get_pixel(decoded_bytes, x - 1, y, width);
-        let b = get_pixel(decoded_bytes, x, y - 1, width);
-        let c = get_pixel(decoded_bytes, x - 1, y - 1, width);
-        let d = get_pixel(decoded_bytes, x + 1, y - 1, width);
-        Self { a, b, c, d}
+    else if p_b <= p_c {
+        b as u8
     }
+    else {
+        c as u8
+    }
+}
 
-    fn paeth(&self, i: usize) -> u8 {
-        let a = self.a[i] as i16;
-        let b = self.b[i] as i16;
-        let c = self.c[i] as i16;
-        let p = a + b - c;
-        let p_a = (p - a).abs();
-        let p_b = (p - b).abs();
-        let p_c = (p - c).abs();
-        if p_a <= p_b && p_a <= p_c {
-            a as u8
-        }
-        else if p_b <= p_c  {
-            b as u8
-        }
-        else {
-            c as u8
-        }
+// A fully-decoded, fully-composited animation frame: `pixels` is already the whole canvas
+// (width * height * 4 BGRA bytes), not just the frame's own region, so displaying a frame is
+// just swapping the render buffer.
+#[allow(dead_code)]
+struct Frame {
+    delay_num: u16,
+    delay_den: u16,
+    pixels: Vec<u8>
+}
+
+impl Frame {
+    #[allow(dead_code)]
+    fn delay(&self) -> Duration {
+        // Per the APNG spec, a denominator of 0 is shorthand for 100 (i.e. delay_num is in
+        // hundredths of a second).
+        let delay_den = if self.delay_den == 0 { 100 } else { self.delay_den };
+        Duration::from_secs_f64(self.delay_num as f64 / delay_den as f64)
     }
 }
 
+// One fcTL's region plus the still-compressed image data that belongs to it (either the default
+// IDAT stream, if it doubles as frame 0, or its fdAT chunks with their sequence numbers stripped).
+struct RawFrame {
+    control: FrameControl,
+    data: Vec<u8>
+}
+
 pub struct PNG {
     chunks: Vec<Chunk>,
     metadata: ImageMetadata,
-    name: String
+    // Only read by the Windows viewer, for the window title.
+    #[allow(dead_code)]
+    name: String,
+    // Only populated for color_type 3 (indexed color); entries are RGB triplets read from PLTE.
+    palette: Option<Vec<[u8; 3]>>,
+    // Only populated when a tRNS chunk is present; for indexed color this is a per-palette-entry
+    // alpha value, indices beyond its length are fully opaque.
+    trns: Option<Vec<u8>>,
+    // Only populated when an acTL chunk is present.
+    animation: Option<AnimationControl>,
+    // Fully composited, ready-to-display frames; empty unless `animation` is populated.
+    frames: Vec<Frame>,
+    // The default image's pixel data, already inflated by `ChunkReader` while the file's chunks
+    // were being read; still filtered and, for sub-8-bit/indexed/interlaced images, still packed.
+    image_data: Vec<u8>
 }
 
 impl fmt::Debug for PNG {
@@ -122,156 +187,450 @@ impl PNG {
         if !file_path.exists() {
             return Err(BadFilePath(fp.to_owned()))
         }
-        match File::open(file_path) {
-            Ok(mut file) => {
-                let mut file_contents: Vec<u8> = Vec::new();
-                match file.read_to_end(&mut file_contents) {
-                    Ok(_file_size) => (),
-                    Err(_e) => return Err(FailedToReadFile(fp.to_owned()))
+        let file = match File::open(file_path) {
+            Ok(file) => file,
+            Err(_e) => return Err(FailedToOpenFile(fp.to_owned()))
+        };
+
+        // The file is fed through `ChunkReader` a buffer at a time rather than read in whole, so
+        // decoding never needs the entire file resident in memory at once.
+        let mut source = BufReader::new(file);
+        let mut decoder = ChunkReader::new();
+        let mut read_buf = [0u8; 8192];
+
+        let mut metadata: Option<ImageMetadata> = None;
+        let mut chunks: Vec<Chunk> = Vec::new();
+        let mut image_data: Vec<u8> = Vec::new();
+        let mut done = false;
+
+        while !done {
+            let bytes_read = source.read(&mut read_buf).map_err(|_e| FailedToReadFile(fp.to_owned()))?;
+            if bytes_read == 0 {
+                return Err(UnexpectedEof());
+            }
+            let mut window = &read_buf[..bytes_read];
+            while !window.is_empty() {
+                let (consumed, event) = decoder.update(window)?;
+                window = &window[consumed..];
+                match event {
+                    Decoded::Nothing | Decoded::ChunkBegin(_, _) => (),
+                    Decoded::Header(meta) => metadata = Some(meta),
+                    Decoded::ImageData(mut bytes) => image_data.append(&mut bytes),
+                    Decoded::ChunkComplete(chunk) => chunks.push(chunk),
+                    Decoded::ImageEnd => done = true
+                }
+                if done {
+                    break;
                 }
-                let mut reader = ChunkReader::new(file_contents)?;
-                let mut chunks: Vec<Chunk> = Vec::new();
-                reader.read_into_vec(&mut chunks)?;
-                let metadata = reader.read_metadata()?;
-                // TODO: I should not be unwrapping
-                let name = file_path.file_stem().unwrap().to_str().unwrap().to_owned();
-                Ok(Self {chunks, metadata, name })
             }
-            Err(_e) => Err(FailedToOpenFile(fp.to_owned()))
         }
+
+        let metadata = metadata.ok_or_else(InvalidHeader)?;
+        let palette = Self::parse_palette(&chunks)?;
+        if metadata.color_type == 3 && palette.is_none() {
+            return Err(BadPaletteChunk());
+        }
+        let trns = Self::parse_trns(&chunks);
+        let animation = Self::parse_animation_control(&chunks)?;
+        // TODO: I should not be unwrapping
+        let name = file_path.file_stem().unwrap().to_str().unwrap().to_owned();
+        let mut png = Self { chunks, metadata, name, palette, trns, animation, frames: Vec::new(), image_data };
+        if png.animation.is_some() {
+            png.frames = png.decode_frames()?;
+        }
+        Ok(png)
     }
 
-    fn filter_decoded_data(&self, unfiltered: Vec<u8>) -> Result<Vec<u8>, DecodeError> {
-        let mut finalized_data: Vec<u8> = Vec::new();
-        // Each scanline has a length of (1 + width * bytes_per_pixel)
-        let bytes_per_pixel = self.get_number_of_channels()? as usize;
-        let scanline_width = 1 + self.metadata.width as usize * bytes_per_pixel;
+    fn parse_animation_control(chunks: &[Chunk]) -> Result<Option<AnimationControl>, DecodeError> {
+        match chunks.iter().find(|chunk| chunk.chunk_type == b"acTL".to_owned()) {
+            Some(chunk) => Ok(Some(AnimationControl::parse(chunk)?)),
+            None => Ok(None)
+        }
+    }
 
-        for y in 0..self.metadata.height as usize {
-            // This gets the current scanline as a slice. It runs from the start of the current
-            // y to its end
-            let scanline: &[u8] = &unfiltered[y * scanline_width..(y + 1) * scanline_width];
-            let mut last_pixel = LastPixel::new();
-            for x in 0..self.metadata.width as usize {
-                last_pixel = LastPixel::from_decoded(&finalized_data, x, y, self.metadata.width);
-
-                // We need to index on scanline[x+1..x+1+bytes_per_pixel] because we need to
-                // account for the filter byte at the start of each scanline
-                let pixel: &[u8] = &scanline[x * bytes_per_pixel + 1..x * bytes_per_pixel + 1 + bytes_per_pixel];
-
-                // TODO: I am not handling all combinations of what the pixels can be here
-                // Ex, for color_type 6 there are 4 pixels in the unfiltered_data but for
-                // color_type 2 there are only 3. I need to still handle 1, 2, and palette
-
-                // We want to swap from RGBA to BGRA, thanks Windows
-                let mut bgra = [pixel[2], pixel[1], pixel[0], 255];
-                match self.metadata.color_type {
-                    6 => bgra[3] = pixel[3],
-                    _ => ()
-                };
-                for i in 0..4 {
-                    bgra[i] = match scanline[0] {
-                        0 => {
-                            bgra[i]
-                        },
-                        1 => {
-                            bgra[i].wrapping_add(last_pixel.a[i])
-                        },
-                        2 => {
-                            bgra[i].wrapping_add(last_pixel.b[i])
-                        },
-                        3 => {
-                            bgra[i].wrapping_add(((last_pixel.a[i] as u16 + last_pixel.b[i] as u16) / 2) as u8)
-                        },
-                        4 => {
-                            bgra[i].wrapping_add(last_pixel.paeth(i))
-                        },
-                        _ => return Err(InvalidScanlineFilter())
-                    }
+    // Walks the chunk list splitting out each fcTL's region and associated (still-compressed)
+    // image data. Whether the default image's IDAT is itself frame 0 depends on whether it
+    // arrived before or after the first fcTL: if before, `current` is still `None` when we see
+    // it and its bytes are simply not collected into a frame.
+    fn split_into_raw_frames(&self) -> Result<Vec<RawFrame>, DecodeError> {
+        let mut frames: Vec<RawFrame> = Vec::new();
+        let mut current: Option<RawFrame> = None;
+        for chunk in &self.chunks {
+            if chunk.chunk_type == b"fcTL".to_owned() {
+                if let Some(frame) = current.take() {
+                    frames.push(frame);
                 }
-                for i in bgra {
-                    finalized_data.push(i);
+                let control = FrameControl::parse(chunk)?;
+                self.validate_frame_region(&control)?;
+                current = Some(RawFrame { control, data: Vec::new() });
+            }
+            else if chunk.chunk_type == b"IDAT".to_owned() {
+                if let Some(frame) = current.as_mut() {
+                    frame.data.extend_from_slice(&chunk.chunk_data);
+                }
+            }
+            else if chunk.chunk_type == b"fdAT".to_owned() {
+                if chunk.chunk_data.len() < 4 {
+                    return Err(InvalidStructure());
+                }
+                if let Some(frame) = current.as_mut() {
+                    // The first 4 bytes are fdAT's own sequence number; the rest decompresses
+                    // exactly like an IDAT stream.
+                    frame.data.extend_from_slice(&chunk.chunk_data[4..]);
+                }
+            }
+        }
+        if let Some(frame) = current.take() {
+            frames.push(frame);
+        }
+        Ok(frames)
+    }
+
+    // A corrupt or malicious fcTL can claim any region, so this has to be checked before the
+    // region is ever used to index into the canvas: `composite_region`/`clear_region` slice
+    // `canvas` at `[y_offset + ry][x_offset + rx]` with no bounds check of their own.
+    fn validate_frame_region(&self, control: &FrameControl) -> Result<(), DecodeError> {
+        if control.width == 0 || control.height == 0 {
+            return Err(InvalidStructure());
+        }
+        let right = control.x_offset.checked_add(control.width).ok_or_else(InvalidStructure)?;
+        let bottom = control.y_offset.checked_add(control.height).ok_or_else(InvalidStructure)?;
+        if right > self.metadata.width || bottom > self.metadata.height {
+            return Err(InvalidStructure());
+        }
+        Ok(())
+    }
+
+    fn decode_frames(&self) -> Result<Vec<Frame>, DecodeError> {
+        let raw_frames = self.split_into_raw_frames()?;
+        let width = self.metadata.width as usize;
+        let height = self.metadata.height as usize;
+
+        let mut canvas = vec![0u8; width * height * 4];
+        let mut previous_canvas = canvas.clone();
+        let mut frames = Vec::with_capacity(raw_frames.len());
+
+        for raw in raw_frames {
+            let region_pixels = self.decode_region(&raw.data, raw.control.width, raw.control.height)?;
+
+            if raw.control.dispose_op == DisposeOp::Previous {
+                previous_canvas.copy_from_slice(&canvas);
+            }
+
+            Self::composite_region(&mut canvas, width, &region_pixels, &raw.control);
+            frames.push(Frame {
+                delay_num: raw.control.delay_num,
+                delay_den: raw.control.delay_den,
+                pixels: canvas.clone()
+            });
+
+            match raw.control.dispose_op {
+                DisposeOp::None => (),
+                DisposeOp::Background => Self::clear_region(&mut canvas, width, &raw.control),
+                DisposeOp::Previous => canvas.copy_from_slice(&previous_canvas)
+            }
+        }
+        Ok(frames)
+    }
+
+    // Blends (or overwrites) `region`'s pixels into `canvas` at the rectangle `control` describes.
+    fn composite_region(canvas: &mut [u8], canvas_width: usize, region: &[u8], control: &FrameControl) {
+        let region_width = control.width as usize;
+        let x_offset = control.x_offset as usize;
+        let y_offset = control.y_offset as usize;
+        for ry in 0..control.height as usize {
+            for rx in 0..region_width {
+                let src = (ry * region_width + rx) * 4;
+                let dst = ((y_offset + ry) * canvas_width + (x_offset + rx)) * 4;
+                let src_pixel = &region[src..src + 4];
+                match control.blend_op {
+                    BlendOp::Source => canvas[dst..dst + 4].copy_from_slice(src_pixel),
+                    BlendOp::Over => {
+                        let src_alpha = src_pixel[3] as u32;
+                        if src_alpha == 0 {
+                            continue;
+                        }
+                        if src_alpha == 255 {
+                            canvas[dst..dst + 4].copy_from_slice(src_pixel);
+                            continue;
+                        }
+                        let dst_alpha = canvas[dst + 3] as u32;
+                        let out_alpha = src_alpha + dst_alpha * (255 - src_alpha) / 255;
+                        if out_alpha == 0 {
+                            canvas[dst..dst + 4].copy_from_slice(&[0, 0, 0, 0]);
+                        } else {
+                            for i in 0..3 {
+                                let s = src_pixel[i] as u32;
+                                let d = canvas[dst + i] as u32;
+                                canvas[dst + i] = ((s * src_alpha + d * dst_alpha * (255 - src_alpha) / 255) / out_alpha) as u8;
+                            }
+                            canvas[dst + 3] = out_alpha as u8;
+                        }
+                    }
                 }
             }
         }
-        Ok(finalized_data)
     }
 
-    fn get_decoded_chunk_data(&mut self) -> Result<Vec<u8>, DecodeError> {
-        // TODO: I think that I should be storing the IDAT data in the PNG struct rather than chunks
-        // Chunks might only be meant to be used to read data during transfer/decoding, not as a
-        // storage mechanism. Will have to see how other chunks, like PLTE, affect the rendering
-        // or reading of IDAT data.
-
-        // Using this method either doubles the memory size of the PNG (data is doubled, we are
-        // storing the compressed data-stream twice and then the compressed data-stream once and
-        // the uncompressed once) if we copy chunk.chunk_data or empties all chunk chunk_data fields
-        let mut data: Vec<u8> = Vec::new();
-        for chunk in &mut self.chunks {
-            if chunk.chunk_type == b"IDAT".to_owned() {
-                data.append(&mut chunk.chunk_data);
+    // Clears `control`'s rectangle in `canvas` to fully transparent black (dispose_op 1).
+    fn clear_region(canvas: &mut [u8], canvas_width: usize, control: &FrameControl) {
+        for ry in 0..control.height as usize {
+            for rx in 0..control.width as usize {
+                let dst = ((control.y_offset as usize + ry) * canvas_width + (control.x_offset as usize + rx)) * 4;
+                canvas[dst..dst + 4].copy_from_slice(&[0, 0, 0, 0]);
             }
-            // TODO: What do I do with non IDAT chunks?
         }
-        let decoded_data = match decompress_to_vec_zlib(&data[..]) {
+    }
+
+    // Inflates a (possibly fdAT-sourced) compressed image stream and unfilters it into a BGRA
+    // buffer sized `width * height * 4`.
+    fn decode_region(&self, compressed: &[u8], width: u32, height: u32) -> Result<Vec<u8>, DecodeError> {
+        let decoded_data = match decompress_to_vec_zlib(compressed) {
             Ok(decoded) => decoded,
             Err(e) => {
                 eprintln!("Failed to inflate compressed data with error: {}", e);
                 return Err(FailedDecoding());
             }
         };
-        let filtered_data = self.filter_decoded_data(decoded_data)?;
-        Ok(filtered_data)
+        self.filter_decoded_data(decoded_data, width as usize, height as usize)
     }
 
-    fn get_number_of_channels(&self) -> Result<u32, DecodeError> {
+    fn parse_palette(chunks: &[Chunk]) -> Result<Option<Vec<[u8; 3]>>, DecodeError> {
+        let plte = match chunks.iter().find(|chunk| chunk.chunk_type == b"PLTE".to_owned()) {
+            Some(chunk) => chunk,
+            None => return Ok(None)
+        };
+        if plte.chunk_data.is_empty() || plte.chunk_data.len() % 3 != 0 || plte.chunk_data.len() / 3 > 256 {
+            return Err(BadPaletteChunk());
+        }
+        Ok(Some(plte.chunk_data.chunks_exact(3).map(|rgb| [rgb[0], rgb[1], rgb[2]]).collect()))
+    }
+
+    fn parse_trns(chunks: &[Chunk]) -> Option<Vec<u8>> {
+        chunks.iter()
+            .find(|chunk| chunk.chunk_type == b"tRNS".to_owned())
+            .map(|chunk| chunk.chunk_data.clone())
+    }
+
+    // Converts a single fully-unfiltered pixel (one 8-bit sample per channel, as produced by
+    // `unpack_samples`) into the BGRA quad our render buffer uses.
+    fn pixel_to_bgra(&self, pixel: &[u8]) -> Result<[u8; 4], DecodeError> {
         match self.metadata.color_type {
-            0 => Ok(1 as u32),
-            2 => {
-                if self.metadata.bit_depth == 8 || self.metadata.bit_depth == 16 {
-                    Ok(3 as u32)
-                }
-                else {
-                    return Err(InvalidStructure())
-                }
+            0 => Ok([pixel[0], pixel[0], pixel[0], 255]),
+            2 => Ok([pixel[2], pixel[1], pixel[0], 255]),
+            3 => {
+                let index = pixel[0] as usize;
+                let palette = self.palette.as_ref().ok_or_else(BadPaletteChunk)?;
+                let rgb = palette.get(index).ok_or_else(BadPaletteChunk)?;
+                let alpha = self.trns.as_ref().and_then(|trns| trns.get(index).copied()).unwrap_or(255);
+                Ok([rgb[2], rgb[1], rgb[0], alpha])
             },
-            3 => Err(UnsupportedFeature("PLTE chunks are not yet supported".to_owned())),
-            4 => {
-                if self.metadata.bit_depth == 8 || self.metadata.bit_depth == 16 {
-                    Ok(2 as u32)
-                }
-                else {
-                    return Err(InvalidStructure())
+            4 => Ok([pixel[0], pixel[0], pixel[0], pixel[1]]),
+            6 => Ok([pixel[2], pixel[1], pixel[0], pixel[3]]),
+            _ => Err(InvalidStructure())
+        }
+    }
+
+    fn filter_decoded_data(&self, unfiltered: Vec<u8>, width: usize, height: usize) -> Result<Vec<u8>, DecodeError> {
+        if self.metadata.interlace_method == 0 {
+            return self.unfilter_scanlines(&unfiltered, width, height);
+        }
+
+        // Adam7: the inflated stream is 7 independent sub-images, each with its own filter byte
+        // per scanline, concatenated in pass order. Unfilter each pass on its own, then scatter
+        // its pixels into the full-size canvas at the coordinates that pass owns.
+        let channels = self.get_channels()? as usize;
+        let mut finalized_data = vec![0u8; width * height * 4];
+        let mut offset = 0usize;
+        for pass in 0..7 {
+            let (pass_width, pass_height) = adam7_pass_dimensions(width, height, pass);
+            if pass_width == 0 || pass_height == 0 {
+                continue;
+            }
+            let pass_row_bytes = self.row_byte_length(pass_width, channels);
+            let pass_byte_len = (1 + pass_row_bytes) * pass_height;
+            let pass_data = &unfiltered[offset..offset + pass_byte_len];
+            offset += pass_byte_len;
+
+            let pass_pixels = self.unfilter_scanlines(pass_data, pass_width, pass_height)?;
+            let col_start = ADAM7_COL_START[pass];
+            let row_start = ADAM7_ROW_START[pass];
+            let col_stride = ADAM7_COL_STRIDE[pass];
+            let row_stride = ADAM7_ROW_STRIDE[pass];
+            for sy in 0..pass_height {
+                for sx in 0..pass_width {
+                    let src = (sy * pass_width + sx) * 4;
+                    let x = col_start + sx * col_stride;
+                    let y = row_start + sy * row_stride;
+                    let dst = (y * width + x) * 4;
+                    finalized_data[dst..dst + 4].copy_from_slice(&pass_pixels[src..src + 4]);
                 }
             }
-            6 => {
-                if self.metadata.bit_depth == 8 || self.metadata.bit_depth == 16 {
-                    Ok(4 as u32)
+        }
+        Ok(finalized_data)
+    }
+
+    // Unfilters a self-contained block of scanlines (either the whole non-interlaced image, or
+    // one Adam7 pass) into a BGRA buffer sized `width * height * 4`.
+    fn unfilter_scanlines(&self, unfiltered: &[u8], width: usize, height: usize) -> Result<Vec<u8>, DecodeError> {
+        let channels = self.get_channels()? as usize;
+        // The distance filtering looks back, in bytes. For bit depths under 8 this is always a
+        // single byte (a whole pixel, or several, still fits in one byte); for 16 bits it's two
+        // bytes per channel.
+        let bpp = self.bytes_per_pixel(channels);
+        let row_bytes = self.row_byte_length(width, channels);
+        let scanline_width = 1 + row_bytes;
+
+        // First pass: reverse the per-scanline filter to recover the raw, still bit-packed row
+        // bytes. This operates purely on bytes `bpp` apart and has no notion of "pixel" yet, so
+        // it's the same regardless of bit depth or color type.
+        let mut reconstructed: Vec<u8> = vec![0u8; row_bytes * height];
+        for y in 0..height {
+            // This gets the current scanline as a slice. It runs from the start of the current
+            // y to its end
+            let scanline: &[u8] = &unfiltered[y * scanline_width..(y + 1) * scanline_width];
+            let filter_type = scanline[0];
+            for i in 0..row_bytes {
+                let a = if i >= bpp { reconstructed[y * row_bytes + i - bpp] } else { 0 };
+                let b = if y > 0 { reconstructed[(y - 1) * row_bytes + i] } else { 0 };
+                let c = if y > 0 && i >= bpp { reconstructed[(y - 1) * row_bytes + i - bpp] } else { 0 };
+                let value = match filter_type {
+                    0 => scanline[1 + i],
+                    1 => scanline[1 + i].wrapping_add(a),
+                    2 => scanline[1 + i].wrapping_add(b),
+                    3 => scanline[1 + i].wrapping_add(((a as u16 + b as u16) / 2) as u8),
+                    4 => scanline[1 + i].wrapping_add(paeth_predictor(a, b, c)),
+                    _ => return Err(InvalidScanlineFilter())
+                };
+                reconstructed[y * row_bytes + i] = value;
+            }
+        }
+
+        // Second pass: unpack the bit-packed row bytes into one 8-bit sample per channel per
+        // pixel, then turn those samples into the BGRA output buffer, e.g. running palette
+        // indices back through PLTE/tRNS or swapping RGB(A) to BGRA.
+        let mut finalized_data: Vec<u8> = Vec::with_capacity(width * height * 4);
+        for row in reconstructed.chunks_exact(row_bytes) {
+            let samples = self.unpack_samples(row, width, channels);
+            for pixel in samples.chunks_exact(channels) {
+                finalized_data.extend_from_slice(&self.pixel_to_bgra(pixel)?);
+            }
+        }
+        Ok(finalized_data)
+    }
+
+    // Expands one already-unfiltered row's packed bytes into `width * channels` 8-bit samples:
+    // sub-byte depths are unpacked MSB-first and scaled up to 8 bits, 16-bit samples are
+    // truncated to their high byte, and 8-bit samples are passed through unchanged.
+    fn unpack_samples(&self, row: &[u8], width: usize, channels: usize) -> Vec<u8> {
+        let bit_depth = self.metadata.bit_depth;
+        let mut samples = Vec::with_capacity(width * channels);
+        match bit_depth {
+            16 => {
+                for sample in row.chunks_exact(2) {
+                    // Windows GDI render buffer is 8 bits per channel, so we only keep the high byte.
+                    samples.push(sample[0]);
                 }
-                else {
-                    return Err(InvalidStructure())
+            },
+            8 => samples.extend_from_slice(row),
+            1 | 2 | 4 => {
+                // Palette indices are used as-is; grayscale samples are scaled so e.g. a 1-bit
+                // sample of 1 becomes white (255) rather than barely-not-black (1).
+                let scale: u8 = match bit_depth {
+                    1 => 255,
+                    2 => 85,
+                    4 => 17,
+                    _ => unreachable!()
+                };
+                let mask = (1u8 << bit_depth) - 1;
+                for sample_index in 0..width * channels {
+                    let bit_offset = sample_index * bit_depth as usize;
+                    let byte = row[bit_offset / 8];
+                    let shift = 8 - bit_depth - (bit_offset % 8) as u8;
+                    let raw_value = (byte >> shift) & mask;
+                    let value = if self.metadata.color_type == 3 { raw_value } else { raw_value * scale };
+                    samples.push(value);
                 }
             },
+            _ => unreachable!()
+        }
+        samples
+    }
+
+    // The default image's pixel data was already inflated by `ChunkReader` while the file was
+    // being read, so this just runs the filter-reconstruction pipeline over it.
+    fn get_decoded_chunk_data(&self) -> Result<Vec<u8>, DecodeError> {
+        self.filter_decoded_data(self.image_data.clone(), self.metadata.width as usize, self.metadata.height as usize)
+    }
+
+    /// Fully decodes this PNG into a plain pixel buffer in the requested channel order, with no
+    /// dependency on any display backend. For an animated image this is the first frame, already
+    /// composited; use the `show` viewer (Windows only) to play the rest of the animation.
+    pub fn decode(&self, order: PixelOrder) -> Result<DecodedImage, DecodeError> {
+        let bgra = match self.frames.first() {
+            Some(frame) => frame.pixels.clone(),
+            None => self.get_decoded_chunk_data()?
+        };
+        let pixels = match order {
+            PixelOrder::Bgra => bgra,
+            PixelOrder::Rgba => Self::swap_red_and_blue(bgra)
+        };
+        Ok(DecodedImage { width: self.metadata.width, height: self.metadata.height, order, pixels })
+    }
+
+    // Swaps the red and blue channels of a BGRA buffer in place, turning it into RGBA.
+    fn swap_red_and_blue(mut pixels: Vec<u8>) -> Vec<u8> {
+        for pixel in pixels.chunks_exact_mut(4) {
+            pixel.swap(0, 2);
+        }
+        pixels
+    }
+
+    // Returns the number of samples (channels) per pixel for this image's color_type, after
+    // checking that its bit_depth is one the PNG spec allows for that color_type.
+    fn get_channels(&self) -> Result<u32, DecodeError> {
+        let allowed_bit_depths: &[u8] = match self.metadata.color_type {
+            0 => &[1, 2, 4, 8, 16],
+            2 => &[8, 16],
+            3 => &[1, 2, 4, 8],
+            4 => &[8, 16],
+            6 => &[8, 16],
             _ => return Err(InvalidStructure())
+        };
+        if !allowed_bit_depths.contains(&self.metadata.bit_depth) {
+            return Err(InvalidStructure());
         }
+        match self.metadata.color_type {
+            0 => Ok(1),
+            2 => Ok(3),
+            // Each sample is a single palette index; the palette lookup happens after
+            // unfiltering, in `pixel_to_bgra`.
+            3 => Ok(1),
+            4 => Ok(2),
+            6 => Ok(4),
+            _ => Err(InvalidStructure())
+        }
+    }
+
+    // The filter "distance back", in bytes: a whole pixel's worth of bits rounded up to 1 byte.
+    fn bytes_per_pixel(&self, channels: usize) -> usize {
+        let bits_per_pixel = channels * self.metadata.bit_depth as usize;
+        ((bits_per_pixel + 7) / 8).max(1)
     }
 
-    fn create_bitmap(&mut self) -> Result<HBITMAP, DecodeError> {
+    // The number of bytes one scanline's packed samples take up, not counting the filter byte.
+    fn row_byte_length(&self, width: usize, channels: usize) -> usize {
+        let bits_per_row = width * channels * self.metadata.bit_depth as usize;
+        (bits_per_row + 7) / 8
+    }
+}
+
+// The GDI/winit viewer, split into its own `impl` block so the rest of `PNG` (parsing, filtering,
+// the portable `decode` API) builds and is testable on any platform.
+#[cfg(windows)]
+impl PNG {
+    fn create_bitmap(&self) -> Result<HBITMAP, DecodeError> {
         let render_data = self.get_decoded_chunk_data()?;
-        // let mut image_data = vec![128u8; 256 * 256 * 4];
-        // for x in 0..256 {
-        //     for y in 0..256 {
-        //         let index = x + y * 256;
-        //         // B
-        //         image_data[index * number_of_channels as usize + 0] = x as u8;
-        //         // G
-        //         image_data[index * number_of_channels as usize + 1] = y as u8;
-        //         // R
-        //         image_data[index * number_of_channels as usize + 2] = 128;
-        //         // A
-        //         image_data[index * number_of_channels as usize + 3] = 255;
-        //     }
-        // }
         unsafe {
             // nbitcount is 8 * 4 rather than 8 * number_of_channels because we always decode back to
             // BGRA, even if the PNG is just B/W or RGB
@@ -279,9 +638,35 @@ impl PNG {
         }
     }
 
+    // Same as `create_bitmap`, but for a pixel buffer that's already decoded (an animation
+    // frame) rather than one this `PNG` needs to decode itself.
+    fn create_bitmap_from_pixels(width: i32, height: i32, pixels: &[u8]) -> HBITMAP {
+        unsafe {
+            CreateBitmap(width, height, 1, 8 * 4, pixels.as_ptr().cast())
+        }
+    }
+
     pub fn show(&mut self) -> Result<(), DecodeError> {
         eprintln!("Displaying image with data:\n{:?}", self);
-        let h_bitmap = self.create_bitmap()?;
+
+        let width = self.metadata.width as i32;
+        let height = self.metadata.height as i32;
+        // Frames are only taken out of `self`, not needed on it anymore, once we're about to
+        // drive the event loop below - the closure owns them from here on.
+        let frames = std::mem::take(&mut self.frames);
+        let is_animated = !frames.is_empty();
+
+        let mut h_bitmap = if is_animated {
+            Self::create_bitmap_from_pixels(width, height, &frames[0].pixels)
+        } else {
+            self.create_bitmap()?
+        };
+        let mut current_frame = 0usize;
+        let mut last_advance = Instant::now();
+        // Per the APNG spec, 0 means loop forever; otherwise stop advancing once the animation
+        // has played through all its frames `num_plays` times, freezing on the last frame.
+        let num_plays = self.animation.as_ref().map(|control| control.num_plays).unwrap_or(0);
+        let mut loops_played = 0u32;
 
         let event_loop = EventLoop::new();
         let window = WindowBuilder::new()
@@ -305,6 +690,21 @@ impl PNG {
                     control_flow.set_exit();
                 }
                 Event::MainEventsCleared => {
+                    if is_animated && frames.len() > 1 && last_advance.elapsed() >= frames[current_frame].delay() {
+                        let next_frame = (current_frame + 1) % frames.len();
+                        let finished_a_loop = next_frame == 0;
+                        if finished_a_loop {
+                            loops_played += 1;
+                        }
+                        if num_plays == 0 || loops_played < num_plays {
+                            current_frame = next_frame;
+                            unsafe {
+                                DeleteObject(h_bitmap);
+                            }
+                            h_bitmap = Self::create_bitmap_from_pixels(width, height, &frames[current_frame].pixels);
+                            last_advance = Instant::now();
+                        }
+                    }
                     window.request_redraw();
                 }
                 Event::RedrawRequested(_) => {
@@ -0,0 +1,245 @@
+// Builds tiny PNG/APNG files by hand (signature, IHDR, chunk CRCs, zlib-compressed IDAT/fdAT)
+// and round-trips them through `PNG::from_file_path`. These are regression tests for the things
+// that are easy to get subtly wrong: bit-packed sample expansion, the indexed/tRNS lookup,
+// Adam7's pass scatter, and which IDAT/fdAT bytes an APNG's first frame is actually built from.
+
+use super::*;
+use crc32fast::Hasher;
+use miniz_oxide::deflate::compress_to_vec_zlib;
+
+const SIGNATURE: [u8; 8] = [137, 80, 78, 71, 13, 10, 26, 10];
+
+fn chunk_bytes(chunk_type: &[u8; 4], data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    out.extend_from_slice(chunk_type);
+    out.extend_from_slice(data);
+    let mut hasher = Hasher::new();
+    hasher.update(chunk_type);
+    hasher.update(data);
+    out.extend_from_slice(&hasher.finalize().to_be_bytes());
+    out
+}
+
+fn ihdr_data(width: u32, height: u32, bit_depth: u8, color_type: u8, interlace_method: u8) -> Vec<u8> {
+    let mut data = Vec::new();
+    data.extend_from_slice(&width.to_be_bytes());
+    data.extend_from_slice(&height.to_be_bytes());
+    data.push(bit_depth);
+    data.push(color_type);
+    data.push(0); // compression method
+    data.push(0); // filter method
+    data.push(interlace_method);
+    data
+}
+
+// Writes `scanlines` (each already prefixed with its filter byte) to a temp file as a complete,
+// non-animated PNG, decodes it, and returns the result. The caller is responsible for making
+// `scanlines`' bit-packing match `bit_depth`/`color_type`.
+fn decode_simple_png(width: u32, height: u32, bit_depth: u8, color_type: u8, interlace_method: u8, scanlines: &[u8], extra_chunks: &[Vec<u8>]) -> Result<DecodedImage, DecodeError> {
+    let compressed = compress_to_vec_zlib(scanlines, 6);
+
+    let mut bytes = SIGNATURE.to_vec();
+    bytes.extend(chunk_bytes(b"IHDR", &ihdr_data(width, height, bit_depth, color_type, interlace_method)));
+    for chunk in extra_chunks {
+        bytes.extend_from_slice(chunk);
+    }
+    bytes.extend(chunk_bytes(b"IDAT", &compressed));
+    bytes.extend(chunk_bytes(b"IEND", &[]));
+
+    let path = temp_path("simple");
+    std::fs::write(&path, &bytes).unwrap();
+    let result = PNG::from_file_path(path.to_str().unwrap()).and_then(|png| png.decode(PixelOrder::Bgra));
+    std::fs::remove_file(&path).ok();
+    result
+}
+
+fn temp_path(name: &str) -> std::path::PathBuf {
+    static COUNTER: std::sync::atomic::AtomicU32 = std::sync::atomic::AtomicU32::new(0);
+    let n = COUNTER.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    let mut path = std::env::temp_dir();
+    path.push(format!("rusty_png_test_{}_{}_{}.png", std::process::id(), name, n));
+    path
+}
+
+#[test]
+fn decodes_indexed_color_with_trns_lookup() {
+    // Two palette entries (red, green); tRNS gives red a half-transparent alpha while leaving
+    // green to fall back to fully opaque, since tRNS is shorter than the palette.
+    let palette = vec![255, 0, 0, 0, 255, 0];
+    let trns = vec![128];
+    let extra = vec![chunk_bytes(b"PLTE", &palette), chunk_bytes(b"tRNS", &trns)];
+
+    // One scanline, filter type 0 (None), two 8-bit indices: 0 (red), 1 (green).
+    let scanlines = vec![0u8, 0, 1];
+    let image = decode_simple_png(2, 1, 8, 3, 0, &scanlines, &extra).unwrap();
+
+    assert_eq!(image.pixels, vec![0, 0, 255, 128, 0, 255, 0, 255]);
+}
+
+#[test]
+fn decodes_bit_depth_1() {
+    // Samples (MSB-first) 1,0,1,1,0,0,1,0 packed into a single byte, scaled 1 -> 255.
+    let scanlines = vec![0u8, 0b1011_0010];
+    let image = decode_simple_png(8, 1, 1, 0, 0, &scanlines, &[]).unwrap();
+    let expected_gray = [255u8, 0, 255, 255, 0, 0, 255, 0];
+    let expected: Vec<u8> = expected_gray.iter().flat_map(|&g| [g, g, g, 255]).collect();
+    assert_eq!(image.pixels, expected);
+}
+
+#[test]
+fn decodes_bit_depth_2() {
+    // Samples 0,1,2,3 packed two bits apiece, scaled by 85 (0, 85, 170, 255).
+    let scanlines = vec![0u8, 0b00_01_10_11];
+    let image = decode_simple_png(4, 1, 2, 0, 0, &scanlines, &[]).unwrap();
+    let expected_gray = [0u8, 85, 170, 255];
+    let expected: Vec<u8> = expected_gray.iter().flat_map(|&g| [g, g, g, 255]).collect();
+    assert_eq!(image.pixels, expected);
+}
+
+#[test]
+fn decodes_bit_depth_4() {
+    // Samples 5, 10 packed four bits apiece, scaled by 17 (85, 170).
+    let scanlines = vec![0u8, (5 << 4) | 10];
+    let image = decode_simple_png(2, 1, 4, 0, 0, &scanlines, &[]).unwrap();
+    assert_eq!(image.pixels, vec![85, 85, 85, 255, 170, 170, 170, 255]);
+}
+
+#[test]
+fn decodes_bit_depth_16() {
+    // A single 16-bit sample, 0xABCD; only the high byte survives into the 8-bit output buffer.
+    let scanlines = vec![0u8, 0xAB, 0xCD];
+    let image = decode_simple_png(1, 1, 16, 0, 0, &scanlines, &[]).unwrap();
+    assert_eq!(image.pixels, vec![0xAB, 0xAB, 0xAB, 255]);
+}
+
+#[test]
+fn decodes_adam7_interlaced_image() {
+    // An 8x8 grayscale image, interlaced, with an arbitrary-but-deterministic pixel value per
+    // coordinate so each Adam7 pass's sub-image can be filled in and later checked independently
+    // of how the passes get scattered back together.
+    const WIDTH: usize = 8;
+    const HEIGHT: usize = 8;
+    fn value_at(x: usize, y: usize) -> u8 {
+        ((x * 31 + y * 17) % 256) as u8
+    }
+
+    let mut scanlines = Vec::new();
+    for pass in 0..7 {
+        let (pass_width, pass_height) = adam7_pass_dimensions(WIDTH, HEIGHT, pass);
+        if pass_width == 0 || pass_height == 0 {
+            continue;
+        }
+        let col_start = ADAM7_COL_START[pass];
+        let row_start = ADAM7_ROW_START[pass];
+        let col_stride = ADAM7_COL_STRIDE[pass];
+        let row_stride = ADAM7_ROW_STRIDE[pass];
+        for sy in 0..pass_height {
+            scanlines.push(0u8); // filter type None
+            for sx in 0..pass_width {
+                let x = col_start + sx * col_stride;
+                let y = row_start + sy * row_stride;
+                scanlines.push(value_at(x, y));
+            }
+        }
+    }
+
+    let image = decode_simple_png(WIDTH as u32, HEIGHT as u32, 8, 0, 1, &scanlines, &[]).unwrap();
+    for &(x, y) in &[(0usize, 0usize), (7, 7), (3, 2), (5, 6)] {
+        let offset = (y * WIDTH + x) * 4;
+        let g = value_at(x, y);
+        assert_eq!(&image.pixels[offset..offset + 4], &[g, g, g, 255], "mismatch at ({}, {})", x, y);
+    }
+}
+
+fn frame_control_data(width: u32, height: u32, delay_num: u16, delay_den: u16) -> Vec<u8> {
+    let mut data = Vec::new();
+    data.extend_from_slice(&0u32.to_be_bytes()); // sequence_number
+    data.extend_from_slice(&width.to_be_bytes());
+    data.extend_from_slice(&height.to_be_bytes());
+    data.extend_from_slice(&0u32.to_be_bytes()); // x_offset
+    data.extend_from_slice(&0u32.to_be_bytes()); // y_offset
+    data.extend_from_slice(&delay_num.to_be_bytes());
+    data.extend_from_slice(&delay_den.to_be_bytes());
+    data.push(0); // dispose_op: None
+    data.push(0); // blend_op: Source
+    data
+}
+
+#[test]
+fn apng_default_image_doubles_as_first_frame_when_fctl_precedes_idat() {
+    // fcTL arrives before IDAT, so the spec says the default image IS the animation's first
+    // frame: its IDAT bytes should get picked up by `split_into_raw_frames`.
+    let pixel = compress_to_vec_zlib(&[0u8, 255, 0, 0], 6); // filter None, one red RGB pixel
+
+    let mut bytes = SIGNATURE.to_vec();
+    bytes.extend(chunk_bytes(b"IHDR", &ihdr_data(1, 1, 8, 2, 0)));
+    let mut actl = Vec::new();
+    actl.extend_from_slice(&1u32.to_be_bytes()); // num_frames
+    actl.extend_from_slice(&1u32.to_be_bytes()); // num_plays
+    bytes.extend(chunk_bytes(b"acTL", &actl));
+    bytes.extend(chunk_bytes(b"fcTL", &frame_control_data(1, 1, 1, 1)));
+    bytes.extend(chunk_bytes(b"IDAT", &pixel));
+    bytes.extend(chunk_bytes(b"IEND", &[]));
+
+    let path = temp_path("apng_default_is_frame0");
+    std::fs::write(&path, &bytes).unwrap();
+    let png = PNG::from_file_path(path.to_str().unwrap()).unwrap();
+    std::fs::remove_file(&path).ok();
+
+    assert_eq!(png.frames.len(), 1);
+    assert_eq!(png.frames[0].pixels, vec![0, 0, 255, 255]); // red, as BGRA
+}
+
+#[test]
+fn apng_default_image_excluded_when_idat_precedes_fctl() {
+    // IDAT arrives before any fcTL, so the default image (blue) is NOT part of the animation;
+    // the only frame comes from the fdAT that follows the fcTL (red).
+    let default_pixel = compress_to_vec_zlib(&[0u8, 0, 0, 255], 6); // filter None, blue RGB pixel
+    let frame_pixel = compress_to_vec_zlib(&[0u8, 255, 0, 0], 6); // filter None, red RGB pixel
+    let mut fdat_data = 1u32.to_be_bytes().to_vec(); // fdAT sequence number
+    fdat_data.extend_from_slice(&frame_pixel);
+
+    let mut bytes = SIGNATURE.to_vec();
+    bytes.extend(chunk_bytes(b"IHDR", &ihdr_data(1, 1, 8, 2, 0)));
+    let mut actl = Vec::new();
+    actl.extend_from_slice(&1u32.to_be_bytes());
+    actl.extend_from_slice(&1u32.to_be_bytes());
+    bytes.extend(chunk_bytes(b"acTL", &actl));
+    bytes.extend(chunk_bytes(b"IDAT", &default_pixel));
+    bytes.extend(chunk_bytes(b"fcTL", &frame_control_data(1, 1, 1, 1)));
+    bytes.extend(chunk_bytes(b"fdAT", &fdat_data));
+    bytes.extend(chunk_bytes(b"IEND", &[]));
+
+    let path = temp_path("apng_default_excluded");
+    std::fs::write(&path, &bytes).unwrap();
+    let png = PNG::from_file_path(path.to_str().unwrap()).unwrap();
+    std::fs::remove_file(&path).ok();
+
+    assert_eq!(png.frames.len(), 1);
+    assert_eq!(png.frames[0].pixels, vec![0, 0, 255, 255]); // red (fdAT), not blue (default image)
+}
+
+#[test]
+fn apng_rejects_fctl_region_that_overflows_the_canvas() {
+    // The canvas is 1x1, but this fcTL claims a 2x2 region at (0, 0) — out of bounds. This must
+    // be rejected before it ever reaches the compositor, which has no bounds check of its own.
+    let pixel = compress_to_vec_zlib(&[0u8, 255, 0, 0], 6); // filter None, one red RGB pixel
+
+    let mut bytes = SIGNATURE.to_vec();
+    bytes.extend(chunk_bytes(b"IHDR", &ihdr_data(1, 1, 8, 2, 0)));
+    let mut actl = Vec::new();
+    actl.extend_from_slice(&1u32.to_be_bytes());
+    actl.extend_from_slice(&1u32.to_be_bytes());
+    bytes.extend(chunk_bytes(b"acTL", &actl));
+    bytes.extend(chunk_bytes(b"fcTL", &frame_control_data(2, 2, 1, 1)));
+    bytes.extend(chunk_bytes(b"IDAT", &pixel));
+    bytes.extend(chunk_bytes(b"IEND", &[]));
+
+    let path = temp_path("apng_oversized_fctl");
+    std::fs::write(&path, &bytes).unwrap();
+    let result = PNG::from_file_path(path.to_str().unwrap());
+    std::fs::remove_file(&path).ok();
+
+    assert!(matches!(result, Err(DecodeError::InvalidStructure())));
+}